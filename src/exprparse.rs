@@ -1,21 +1,56 @@
-use std::{collections::LinkedList, fmt::{Debug, Display}, str::FromStr};
+use std::{collections::{HashMap, LinkedList}, fmt::{Debug, Display}, str::FromStr};
 use regex::Regex;
 
 #[derive(Debug, PartialEq)]
 pub enum PostfixError {
-    ParseError,
-    EmptyString
+    EmptyString,
+    UndefinedVariable(String),
+    UnexpectedToken { pos: usize, text: String },
+    UnmatchedParen { pos: usize },
+    MissingOperand,
+    DivisionByZero
 }
 
+impl Display for PostfixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostfixError::EmptyString => write!(f, "empty expression"),
+            PostfixError::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            PostfixError::UnexpectedToken { pos, text } => {
+                write!(f, "unexpected token `{}` at position {}", text, pos)
+            }
+            PostfixError::UnmatchedParen { pos } => write!(f, "unmatched parenthesis at position {}", pos),
+            PostfixError::MissingOperand => write!(f, "missing operand"),
+            PostfixError::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for PostfixError {}
+
 #[derive(Debug, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
 enum Tokens {
     Add,
     Sub,
     Mul,
     Div,
     Exp,
+    Lt,
+    Gt,
+    Eq,
+    Ne,
+    BitAnd,
+    BitOr,
     OpP,
     ClP,
+    Func(String, fn(f64) -> f64),
+    Ident(String),
     Num(f64)
 }
 impl Display for Tokens {
@@ -26,8 +61,16 @@ impl Display for Tokens {
             Tokens::Mul => write!(f, "*"),
             Tokens::Div => write!(f, "/"),
             Tokens::Exp => write!(f, "^"),
+            Tokens::Lt => write!(f, "<"),
+            Tokens::Gt => write!(f, ">"),
+            Tokens::Eq => write!(f, "=="),
+            Tokens::Ne => write!(f, "!="),
+            Tokens::BitAnd => write!(f, "&"),
+            Tokens::BitOr => write!(f, "|"),
             Tokens::OpP => write!(f, "("),
             Tokens::ClP => write!(f, ")"),
+            Tokens::Func(name, _) => write!(f, "{}", name),
+            Tokens::Ident(name) => write!(f, "[{}]", name),
             Tokens::Num(num) => write!(f, "[{}]", num),
         }
     }
@@ -38,33 +81,111 @@ impl FromStr for Tokens {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Ok(num) = s.parse::<f64>() {
             return Ok(Tokens::Num(num));
-        } 
-        
+        }
+
         match s {
             "+" => Ok(Tokens::Add),
             "-" => Ok(Tokens::Sub),
             "*" => Ok(Tokens::Mul),
             "/" => Ok(Tokens::Div),
             "^" => Ok(Tokens::Exp),
+            "<" => Ok(Tokens::Lt),
+            ">" => Ok(Tokens::Gt),
+            "==" => Ok(Tokens::Eq),
+            "!=" => Ok(Tokens::Ne),
+            "&" => Ok(Tokens::BitAnd),
+            "|" => Ok(Tokens::BitOr),
             "(" => Ok(Tokens::OpP),
             ")" => Ok(Tokens::ClP),
-            _ => Err(PostfixError::ParseError)
+            _ => match Tokens::resolve_fn(s) {
+                Some(func) => Ok(Tokens::Func(s.to_string(), func)),
+                // Any other run of name characters is a variable reference,
+                // resolved against the environment at evaluation time.
+                None if s.chars().all(|ch| ch.is_ascii_alphabetic() || ch == '_') => {
+                    Ok(Tokens::Ident(s.to_string()))
+                }
+                // Position is filled in by the tokenizer, which knows the offset.
+                None => Err(PostfixError::UnexpectedToken { pos: 0, text: s.to_string() }),
+            },
         }
     }
 }
 impl Tokens {
     fn prio(&self) -> usize {
         match self {
-            Tokens::Add => 1,
-            Tokens::Sub => 1,
-            Tokens::Mul => 2,
-            Tokens::Div => 2,
-            Tokens::Exp => 3,
+            Tokens::BitAnd => 1,
+            Tokens::BitOr => 1,
+            Tokens::Lt => 2,
+            Tokens::Gt => 2,
+            Tokens::Eq => 2,
+            Tokens::Ne => 2,
+            Tokens::Add => 3,
+            Tokens::Sub => 3,
+            Tokens::Mul => 4,
+            Tokens::Div => 4,
+            Tokens::Exp => 5,
+            Tokens::Func(..) => 6,
             _ => 0,
         }
     }
+
+    fn assoc(&self) -> Assoc {
+        match self {
+            Tokens::Exp => Assoc::Right,
+            _ => Assoc::Left,
+        }
+    }
+
+    fn resolve_fn(name: &str) -> Option<fn(f64) -> f64> {
+        Some(match name {
+            "sin" => f64::sin,
+            "cos" => f64::cos,
+            "tan" => f64::tan,
+            "sqrt" => f64::sqrt,
+            "ln" => f64::ln,
+            "log" => f64::log10,
+            "abs" => f64::abs,
+            "floor" => f64::floor,
+            _ => return None,
+        })
+    }
+
+    fn builtin_const(name: &str) -> Option<f64> {
+        match name {
+            "pi" => Some(std::f64::consts::PI),
+            "e" => Some(std::f64::consts::E),
+            _ => None,
+        }
+    }
+}
+
+/// Expression tree reconstructed from the flat postfix stream.
+///
+/// Unlike the [`Postfix`] list this keeps the grouping explicit, which makes it
+/// a convenient target for tree passes (e.g. constant folding) and for a
+/// fully-parenthesized dump of how an expression was actually parsed.
+#[derive(Debug)]
+pub enum Ast {
+    Num(f64),
+    Var(String),
+    BinOp { op: String, left: Box<Ast>, right: Box<Ast> },
+    UnaryNeg(Box<Ast>),
+    Func(String, Box<Ast>),
 }
 
+impl Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ast::Num(num) => write!(f, "{}", num),
+            Ast::Var(name) => write!(f, "{}", name),
+            Ast::BinOp { op, left, right } => write!(f, "({} {} {})", left, op, right),
+            Ast::UnaryNeg(inner) => write!(f, "(-{})", inner),
+            Ast::Func(name, arg) => write!(f, "{}({})", name, arg),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Postfix {
     stack: LinkedList<Tokens>
 }
@@ -81,28 +202,43 @@ impl Postfix {
             return Err(PostfixError::EmptyString);
         }
     
-        let re = Regex::new(r"\-?\d+(\.\d+)?|[\+\-\*/\^\(\)]").map_err(|_| PostfixError::ParseError)?;
-        if re.split(infix)
-            .any(|s| !s.trim().is_empty() && !s.trim().chars().all(|ch| ch.is_whitespace()))
-        {
-            return Err(PostfixError::ParseError);
-        }
-    
+        let re = Regex::new(r"\-?\d+(\.\d+)?|[A-Za-z_]+|==|!=|[\+\-\*/\^\(\)<>&|]")
+            .expect("token regex is valid");
+
         let mut stack: Vec<Tokens> = Vec::new();
+        let mut open_parens: Vec<usize> = Vec::new();
         let mut postfix = Postfix { stack: LinkedList::new() };
         let mut last_is_num = false;
-    
-        for token in re.find_iter(infix).map(|mat| mat.as_str()) {
+        let mut last_func_pos: Option<usize> = None;
+        let mut last_end = 0;
+
+        for mat in re.find_iter(infix) {
+            // Anything the tokenizer skipped over is unrecognised input.
+            if let Some(off) = infix[last_end..mat.start()].find(|ch: char| !ch.is_whitespace()) {
+                let pos = last_end + off;
+                return Err(PostfixError::UnexpectedToken { pos, text: infix[pos..mat.start()].trim().to_string() });
+            }
+            last_end = mat.end();
+            let token = mat.as_str();
+
+            // A function name is only meaningful when it is immediately applied,
+            // e.g. `sin(0)`; a bare `sin` without its argument list is a parse error.
+            if last_func_pos.is_some() && token != "(" {
+                return Err(PostfixError::UnexpectedToken { pos: mat.start(), text: token.to_string() });
+            }
+
             if let Ok(num) = token.parse::<f64>() {
                 postfix.stack.push_back(Tokens::Num(num));
                 last_is_num = true;
+                last_func_pos = None;
             } else {
                 match token {
                     "(" => {
                         if last_is_num {
-                            stack.push(Tokens::Mul); // 2(5) can mean 2 * (5) 
+                            stack.push(Tokens::Mul); // 2(5) can mean 2 * (5)
                         }
                         stack.push(Tokens::OpP);
+                        open_parens.push(mat.start());
                     },
                     ")" => {
                         let mut found_open_paren = false;
@@ -114,13 +250,45 @@ impl Postfix {
                             postfix.stack.push_back(top);
                         }
                         if !found_open_paren {
-                            return Err(PostfixError::ParseError);
+                            return Err(PostfixError::UnmatchedParen { pos: mat.start() });
+                        }
+                        open_parens.pop();
+                        // A closing paren also discharges the function that owns it.
+                        if matches!(stack.last(), Some(Tokens::Func(..))) {
+                            postfix.stack.push_back(stack.pop().unwrap());
                         }
                     }
                     _ => {
-                        let parsed_token: Tokens = token.parse().map_err(|_| PostfixError::ParseError)?;
+                        let parsed_token: Tokens = token.parse()
+                            .map_err(|_| PostfixError::UnexpectedToken { pos: mat.start(), text: token.to_string() })?;
+                        if matches!(parsed_token, Tokens::Ident(_)) {
+                            // Identifiers are operands; their value is resolved later.
+                            postfix.stack.push_back(parsed_token);
+                            last_is_num = true;
+                            last_func_pos = None;
+                            continue;
+                        }
+                        if matches!(parsed_token, Tokens::Func(..)) {
+                            if last_is_num {
+                                stack.push(Tokens::Mul); // 2 sin(0) can mean 2 * sin(0)
+                            }
+                            // Functions bind tighter than any operator and are
+                            // discharged by their matching `)`, so they are simply
+                            // stacked to await their argument.
+                            stack.push(parsed_token);
+                            last_is_num = false;
+                            last_func_pos = Some(mat.start());
+                            continue;
+                        }
                         while let Some(top) = stack.last() {
-                            if parsed_token.prio() > top.prio() {
+                            // Left-associative operators also pop an equal-priority
+                            // top (`5 - 2 + 1` groups left); right-associative ones
+                            // (`^`) keep it, so `2 ^ 3 ^ 2` groups right.
+                            let pop_top = match parsed_token.assoc() {
+                                Assoc::Left => top.prio() >= parsed_token.prio(),
+                                Assoc::Right => top.prio() > parsed_token.prio(),
+                            };
+                            if !pop_top {
                                 break;
                             }
                             postfix.stack.push_back(stack.pop().unwrap());
@@ -129,49 +297,120 @@ impl Postfix {
                     }
                 }
                 last_is_num = false;
+                last_func_pos = None;
             }
         }
-    
+
+        // Trailing unrecognised input after the last matched token.
+        if let Some(off) = infix[last_end..].find(|ch: char| !ch.is_whitespace()) {
+            let pos = last_end + off;
+            return Err(PostfixError::UnexpectedToken { pos, text: infix[pos..].trim().to_string() });
+        }
+        if let Some(pos) = last_func_pos {
+            return Err(PostfixError::UnexpectedToken { pos, text: "function without argument".to_string() });
+        }
+        if let Some(&pos) = open_parens.first() {
+            return Err(PostfixError::UnmatchedParen { pos });
+        }
+
         while let Some(token) = stack.pop() {
             postfix.stack.push_back(token);
         }
-    
+
         Ok(postfix)
-    }  
+    }
 
+    /// Evaluate the expression with no variable bindings, using only the
+    /// built-in constants. The REPL threads an environment through
+    /// [`Postfix::evaluate_with`]; this wrapper is the convenience entry point
+    /// for callers (and tests) that have no bindings of their own.
+    #[allow(dead_code)]
     pub fn evaluate(&self) -> Result<f64, PostfixError> {
+        self.evaluate_with(&Default::default())
+    }
+
+    /// Evaluate the expression, resolving [`Tokens::Ident`]s against `env`
+    /// (falling back to built-in constants like `pi` and `e`).
+    pub fn evaluate_with(&self, env: &HashMap<String, f64>) -> Result<f64, PostfixError> {
         let mut stack: Vec<f64> = Vec::new();
 
         for token in &self.stack {
             match token {
                 Tokens::Num(num) => stack.push(*num),
+                Tokens::Ident(name) => {
+                    let val = env
+                        .get(name)
+                        .copied()
+                        .or_else(|| Tokens::builtin_const(name))
+                        .ok_or_else(|| PostfixError::UndefinedVariable(name.clone()))?;
+                    stack.push(val);
+                }
+                Tokens::Func(_, func) => {
+                    let val = stack.pop().ok_or(PostfixError::MissingOperand)?;
+                    stack.push(func(val));
+                }
                 _ => {
-                    let val1 = stack.pop().ok_or(PostfixError::ParseError)?;
-                    let val2 = stack.pop().ok_or(PostfixError::ParseError)?;
+                    let val1 = stack.pop().ok_or(PostfixError::MissingOperand)?;
+                    let val2 = stack.pop().ok_or(PostfixError::MissingOperand)?;
                     match token {
                         Tokens::Add => stack.push(val2 + val1),
                         Tokens::Sub => stack.push(val2 - val1),
                         Tokens::Mul => stack.push(val2 * val1),
+                        Tokens::Div if val1 == 0.0 => return Err(PostfixError::DivisionByZero),
                         Tokens::Div => stack.push(val2 / val1),
                         Tokens::Exp => stack.push(val2.powf(val1)),
+                        Tokens::Lt => stack.push((val2 < val1) as u8 as f64),
+                        Tokens::Gt => stack.push((val2 > val1) as u8 as f64),
+                        Tokens::Eq => stack.push((val2 == val1) as u8 as f64),
+                        Tokens::Ne => stack.push((val2 != val1) as u8 as f64),
+                        Tokens::BitAnd => stack.push(((val2 as i64) & (val1 as i64)) as f64),
+                        Tokens::BitOr => stack.push(((val2 as i64) | (val1 as i64)) as f64),
                         _ => {},
                     }
                 }
             }
         }
 
-        stack.pop().ok_or(PostfixError::ParseError)
-    }      
-}
+        stack.pop().ok_or(PostfixError::MissingOperand)
+    }
+
+    /// Rebuild an [`Ast`] from the postfix stream, mirroring the stack machine
+    /// that [`Postfix::evaluate`] uses but collecting sub-trees instead of values.
+    pub fn to_ast(&self) -> Result<Ast, PostfixError> {
+        let mut stack: Vec<Ast> = Vec::new();
 
+        for token in &self.stack {
+            match token {
+                Tokens::Num(num) if *num < 0.0 => {
+                    stack.push(Ast::UnaryNeg(Box::new(Ast::Num(-num))));
+                }
+                Tokens::Num(num) => stack.push(Ast::Num(*num)),
+                Tokens::Ident(name) => stack.push(Ast::Var(name.clone())),
+                Tokens::Func(name, _) => {
+                    let arg = stack.pop().ok_or(PostfixError::MissingOperand)?;
+                    stack.push(Ast::Func(name.clone(), Box::new(arg)));
+                }
+                op => {
+                    let right = stack.pop().ok_or(PostfixError::MissingOperand)?;
+                    let left = stack.pop().ok_or(PostfixError::MissingOperand)?;
+                    stack.push(Ast::BinOp {
+                        op: op.to_string(),
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    });
+                }
+            }
+        }
 
-pub fn parse_expression(expr: &str) {
-    match Postfix::from_infix(expr).and_then(|p| p.evaluate()) {
-        Ok(result) => {println!("{expr} = {result}")},
-        Err(err) => println!("Error: {err:?}"),
+        stack.pop().ok_or(PostfixError::MissingOperand)
     }
 }
 
+
+pub fn parse_expression(expr: &str, env: &HashMap<String, f64>) -> Result<f64, PostfixError> {
+    Postfix::from_infix(expr).and_then(|p| p.evaluate_with(env))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,7 +458,21 @@ mod tests {
         assert_postfix_result("-2 + 3", "[-2][3]+", Some(1.0));
         assert_postfix_result("-3^2", "[-3][2]^", Some(9.0));
         assert_postfix_error("", PostfixError::EmptyString);
-        assert_postfix_error("2 + a", PostfixError::ParseError);
+        assert_postfix_error("2 + a", PostfixError::UndefinedVariable("a".to_string()));
+    }
+
+    #[test]
+    fn test_variables_and_constants() {
+        let mut env = std::collections::HashMap::new();
+        env.insert("x".to_string(), 11.0);
+        let value = Postfix::from_infix("x * 2").unwrap().evaluate_with(&env).unwrap();
+        assert!((value - 22.0).abs() < 1e-9);
+
+        let pi = Postfix::from_infix("pi").unwrap().evaluate().unwrap();
+        assert!((pi - std::f64::consts::PI).abs() < 1e-9);
+
+        let err = Postfix::from_infix("y + 1").unwrap().evaluate();
+        assert_eq!(err, Err(PostfixError::UndefinedVariable("y".to_string())));
     }
 
     #[test]
@@ -238,13 +491,48 @@ mod tests {
     #[test]
     fn test_operator_precedence() {
         assert_postfix_result("5 - 2 + 1", "[5][2]-[1]+", Some(4.0));
-        assert_postfix_result("2 ^ 3 ^ 2", "[2][3]^[2]^", Some(64.0));
+        assert_postfix_result("2 ^ 3 ^ 2", "[2][3][2]^^", Some(512.0));
+    }
+
+    #[test]
+    fn test_comparison_and_bitwise() {
+        assert_postfix_result("2 > 1", "[2][1]>", Some(1.0));
+        assert_postfix_result("3 != 3", "[3][3]!=", Some(0.0));
+        assert_postfix_result("1 + 2 > 2", "[1][2]+[2]>", Some(1.0));
+        assert_postfix_result("(2 + 3) > 4 == 1", "[2][3]+[4]>[1]==", Some(1.0));
+        assert_postfix_result("5 & 3", "[5][3]&", Some(1.0));
+        assert_postfix_result("5 | 2", "[5][2]|", Some(7.0));
+        assert_postfix_result("1 | 0 == 0", "[1][0][0]==|", Some(1.0));
+    }
+
+    #[test]
+    fn test_ast_display() {
+        let ast = Postfix::from_infix("2 + 3 * 4").unwrap().to_ast().unwrap();
+        assert_eq!(ast.to_string(), "(2 + (3 * 4))");
+        let ast = Postfix::from_infix("(2 + 3) * 4").unwrap().to_ast().unwrap();
+        assert_eq!(ast.to_string(), "((2 + 3) * 4)");
+        let ast = Postfix::from_infix("sqrt(2)").unwrap().to_ast().unwrap();
+        assert_eq!(ast.to_string(), "sqrt(2)");
+        let ast = Postfix::from_infix("-3 ^ 2").unwrap().to_ast().unwrap();
+        assert_eq!(ast.to_string(), "((-3) ^ 2)");
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_postfix_result("sqrt(2) + sin(0)", "[2]sqrt[0]sin+", Some(2.0_f64.sqrt()));
+        assert_postfix_result("abs(-3)", "[-3]abs", Some(3.0));
+        assert_postfix_result("floor(2.7) + 1", "[2.7]floor[1]+", Some(3.0));
+        assert_postfix_result("sqrt(sqrt(16))", "[16]sqrtsqrt", Some(2.0));
+        assert_postfix_error("sin 0", PostfixError::UnexpectedToken { pos: 4, text: "0".to_string() });
+        assert_postfix_error("sin", PostfixError::UnexpectedToken { pos: 0, text: "function without argument".to_string() });
     }
 
     #[test]
     fn test_error_cases() {
-        assert_postfix_error("(2 + 3", PostfixError::ParseError);
-        assert_postfix_error("2 + 3)", PostfixError::ParseError);
-        assert_postfix_error("2 +", PostfixError::ParseError);
+        assert_postfix_error("(2 + 3", PostfixError::UnmatchedParen { pos: 0 });
+        assert_postfix_error("2 + 3)", PostfixError::UnmatchedParen { pos: 5 });
+        assert_postfix_error("2 +", PostfixError::MissingOperand);
+        assert_postfix_error("4 / 0", PostfixError::DivisionByZero);
+        assert_postfix_error("2 $ 3", PostfixError::UnexpectedToken { pos: 2, text: "$".to_string() });
     }
 }